@@ -0,0 +1,373 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Drives the backup side of a state snapshot backup: pulls account state from a running node's
+//! backup service, cuts it into content-defined, digest-addressed chunks (so two snapshots that
+//! share most accounts share most chunks too), and writes it out to a `BackupStorage`, producing
+//! a manifest that a `StateSnapshotRestoreController` can later use to reconstruct the state on
+//! another `LibraDB`.
+
+use crate::catalog::Catalog;
+use crate::chunker::{Chunker, ChunkerOpt};
+use crate::crypto::{CryptMode, CryptoOpt, EncryptionKey};
+use crate::storage::{BackupStorage, FileHandle};
+use anyhow::{ensure, Result};
+use libra_crypto::HashValue;
+use libra_types::{
+    account_state_blob::AccountStateBlob, proof::SparseMerkleRangeProof, transaction::Version,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+use std::sync::Arc;
+use structopt::StructOpt;
+use tokio::io::AsyncReadExt;
+
+/// How many (key, blob) pairs to request from the backup service per network round trip. This is
+/// purely a transport-level page size; it has no bearing on where chunk boundaries fall.
+const FETCH_PAGE_SIZE: usize = 1024;
+
+/// Options shared by every backup subcommand.
+#[derive(Clone, StructOpt)]
+pub struct GlobalBackupOpt {
+    /// A chunk boundary is cut, on average, every this many bytes of serialized account state.
+    #[structopt(long, default_value = "1048576")]
+    pub avg_chunk_size: usize,
+
+    /// No chunk is ever cut smaller than this, to avoid pathologically small chunks.
+    #[structopt(long, default_value = "262144")]
+    pub min_chunk_size: usize,
+
+    /// No chunk is ever cut larger than this, bounding memory use and per-chunk upload size.
+    #[structopt(long, default_value = "4194304")]
+    pub max_chunk_size: usize,
+
+    #[structopt(flatten)]
+    pub crypto: CryptoOpt,
+}
+
+impl GlobalBackupOpt {
+    fn chunker_opt(&self) -> ChunkerOpt {
+        ChunkerOpt {
+            min_chunk_size: self.min_chunk_size,
+            avg_chunk_size: self.avg_chunk_size,
+            max_chunk_size: self.max_chunk_size,
+        }
+    }
+}
+
+#[derive(Clone, StructOpt)]
+pub struct StateSnapshotBackupOpt {
+    /// The version whose state to back up.
+    #[structopt(long)]
+    pub version: Version,
+}
+
+/// A thin client for the node-side backup service, responsible for streaming account state out
+/// of a running `LibraDB` over HTTP.
+pub struct BackupServiceClient {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl BackupServiceClient {
+    pub fn new(port: u16) -> Self {
+        Self {
+            base_url: format!("http://localhost:{}", port),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Returns the latest committed version and its account state root hash.
+    pub async fn get_latest_state_root(&self) -> Result<(Version, HashValue)> {
+        let url = format!("{}/latest_state_root", self.base_url);
+        let bytes = self.client.get(&url).send().await?.error_for_status()?.bytes().await?;
+        Ok(lcs::from_bytes(&bytes)?)
+    }
+
+    /// Streams up to `limit` (key, blob) pairs of account state at `version`, starting at
+    /// `start_idx`.
+    pub async fn get_state_range(
+        &self,
+        version: Version,
+        start_idx: usize,
+        limit: usize,
+    ) -> Result<Vec<(HashValue, AccountStateBlob)>> {
+        let url = format!(
+            "{}/state_range/{}/{}/{}",
+            self.base_url, version, start_idx, limit
+        );
+        let bytes = self.client.get(&url).send().await?.error_for_status()?.bytes().await?;
+        Ok(lcs::from_bytes(&bytes)?)
+    }
+
+    /// Returns a `SparseMerkleRangeProof` proving that `[first_idx, last_idx]` is the exact,
+    /// contiguous slice of account state at `version` it claims to be, so a restore (or verify)
+    /// can check each chunk against the root hash as it streams in rather than only at the end.
+    /// Requested per output chunk rather than per `get_state_range` page, since content-defined
+    /// chunk boundaries don't line up with the fixed-size pages `get_state_range` fetches.
+    pub async fn get_state_range_proof(
+        &self,
+        version: Version,
+        first_idx: usize,
+        last_idx: usize,
+    ) -> Result<SparseMerkleRangeProof> {
+        let url = format!(
+            "{}/state_range_proof/{}/{}/{}",
+            self.base_url, version, first_idx, last_idx
+        );
+        let bytes = self.client.get(&url).send().await?.error_for_status()?.bytes().await?;
+        Ok(lcs::from_bytes(&bytes)?)
+    }
+}
+
+/// One chunk of a state snapshot: a contiguous run of accounts (ordered by key), content-addressed
+/// by the SHA-256 digest of its serialized plaintext bytes. `BackupStorage` stores each
+/// chunk once under its digest, so a restore need only know the digest to fetch it, and a backup
+/// need only check whether that digest already exists to skip re-uploading it.
+#[derive(Serialize, Deserialize)]
+pub struct StateSnapshotChunk {
+    pub first_idx: usize,
+    pub last_idx: usize,
+    pub first_key: HashValue,
+    pub last_key: HashValue,
+    pub digest: String,
+    /// Proves this chunk's leaves are the exact range `[first_idx, last_idx]` of the account
+    /// state tree at `version`, so `add_chunk` can verify it incrementally. Unlike `digest`, this
+    /// is positional rather than content-derived, so it's kept out of `StateSnapshotChunkContent`
+    /// and lives here instead, alongside the rest of this chunk's placement metadata; otherwise
+    /// identical chunks at different positions (or versions) would never dedup.
+    pub proof: SparseMerkleRangeProof,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StateSnapshotChunkContent {
+    pub blobs: Vec<(HashValue, AccountStateBlob)>,
+}
+
+/// Describes a completed state snapshot backup: which version it's of, what the account state
+/// root hash was at that version, and the ordered list of chunks that make it up.
+#[derive(Serialize, Deserialize)]
+pub struct StateSnapshotBackupManifest {
+    pub version: Version,
+    pub root_hash: HashValue,
+    /// Unix timestamp (seconds) this backup completed at, used by `PruneController` to bucket
+    /// snapshots into keep-hourly/daily/weekly/monthly/yearly retention categories.
+    pub created_at: u64,
+    pub chunks: Vec<StateSnapshotChunk>,
+    pub crypt_mode: CryptMode,
+    /// Set iff `crypt_mode` is `Encrypt`; lets a restore confirm it was handed the right key
+    /// before trusting anything else in this manifest.
+    pub key_fingerprint: Option<String>,
+    /// Set iff the key was derived from a passphrase, so a restore can re-derive the same key.
+    pub key_salt: Option<[u8; 16]>,
+}
+
+/// Where a chunk identified by `digest` is stored in a `BackupStorage`. Both backup and restore
+/// derive this the same way, so neither needs to persist it anywhere beyond the digest itself.
+///
+/// `key_fingerprint` scopes the handle to the key a chunk was encrypted under: the digest is
+/// computed over plaintext, so without this, two backups encrypting the same account state under
+/// different keys would collide on the same handle and the second run would wrongly "dedup" onto
+/// ciphertext it can't decrypt with its own key. Unencrypted chunks (`key_fingerprint: None`) keep
+/// the bare digest path so existing repositories don't change shape.
+pub fn chunk_handle(digest: &str, key_fingerprint: Option<&str>) -> FileHandle {
+    match key_fingerprint {
+        Some(fingerprint) => format!("chunks/{}/{}.chunk", fingerprint, digest),
+        None => format!("chunks/{}.chunk", digest),
+    }
+}
+
+/// `BackupStorage` handle of the salt used to derive a passphrase-based key, persisted once per
+/// repository and reused by every subsequent backup so the same `--passphrase` always derives the
+/// same key. Without this, each run would pick its own random salt and two backups of the same
+/// passphrase would (silently) encrypt under different keys, defeating cross-run chunk dedup.
+const REPOSITORY_SALT_HANDLE: &str = "key_salt";
+
+pub struct StateSnapshotBackupController {
+    opt: StateSnapshotBackupOpt,
+    global_opt: GlobalBackupOpt,
+    client: Arc<BackupServiceClient>,
+    storage: Arc<dyn BackupStorage>,
+    catalog: Option<Arc<Catalog>>,
+}
+
+impl StateSnapshotBackupController {
+    pub fn new(
+        opt: StateSnapshotBackupOpt,
+        global_opt: GlobalBackupOpt,
+        client: Arc<BackupServiceClient>,
+        storage: Arc<dyn BackupStorage>,
+        catalog: Option<Arc<Catalog>>,
+    ) -> Self {
+        Self {
+            opt,
+            global_opt,
+            client,
+            storage,
+            catalog,
+        }
+    }
+
+    /// Loads this repository's passphrase-derivation salt, generating and persisting one the
+    /// first time a backup with `--passphrase` is run against it. Reusing one salt per repository
+    /// (rather than a fresh one per run) is what lets the same `--passphrase` derive the same key
+    /// across runs, so cross-run chunk dedup never reuses a chunk encrypted under a key the
+    /// current run can't decrypt with.
+    async fn load_or_create_salt(&self) -> Result<Option<[u8; 16]>> {
+        if self.global_opt.crypto.crypt_mode != CryptMode::Encrypt
+            || self.global_opt.crypto.passphrase.is_none()
+        {
+            return Ok(None);
+        }
+        if self.storage.exists(&REPOSITORY_SALT_HANDLE.to_string()).await? {
+            let mut reader = self
+                .storage
+                .open_for_read(&REPOSITORY_SALT_HANDLE.to_string())
+                .await?;
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf).await?;
+            ensure!(
+                buf.len() == 16,
+                "repository salt file is corrupt: expected 16 bytes, got {}",
+                buf.len()
+            );
+            Ok(Some(buf.try_into().expect("length checked above")))
+        } else {
+            let salt = EncryptionKey::random_salt();
+            let (_, mut writer) = self
+                .storage
+                .create_for_write(&REPOSITORY_SALT_HANDLE.to_string())
+                .await?;
+            tokio::io::copy(&mut salt.as_slice(), &mut writer).await?;
+            Ok(Some(salt))
+        }
+    }
+
+    /// Writes out `pending`, a just-finalized chunk's worth of records, as a content-addressed
+    /// chunk (skipping the upload if a chunk with the same digest is already stored under the
+    /// same key), and returns the manifest entry describing it.
+    async fn flush_chunk(
+        &self,
+        key: &Option<EncryptionKey>,
+        key_fingerprint: Option<&str>,
+        first_idx: usize,
+        pending: Vec<(HashValue, AccountStateBlob)>,
+    ) -> Result<StateSnapshotChunk> {
+        let last_idx = first_idx + pending.len() - 1;
+        let first_key = pending.first().expect("checked non-empty").0;
+        let last_key = pending.last().expect("checked non-empty").0;
+
+        let content = StateSnapshotChunkContent { blobs: pending };
+        let bytes = lcs::to_bytes(&content)?;
+        let digest = hex::encode(Sha256::digest(&bytes));
+        let handle = chunk_handle(&digest, key_fingerprint);
+
+        if !self.storage.exists(&handle).await? {
+            let to_store = match key {
+                Some(key) => key.encrypt(&bytes)?,
+                None => bytes,
+            };
+            let (_, mut writer) = self.storage.create_for_write(&handle).await?;
+            tokio::io::copy(&mut to_store.as_slice(), &mut writer).await?;
+        }
+
+        // The proof is positional, not content-derived, so it has to be fetched fresh for every
+        // chunk regardless of whether its content was just deduplicated against an existing one.
+        let proof = self
+            .client
+            .get_state_range_proof(self.opt.version, first_idx, last_idx)
+            .await?;
+
+        Ok(StateSnapshotChunk {
+            first_idx,
+            last_idx,
+            first_key,
+            last_key,
+            digest,
+            proof,
+        })
+    }
+
+    /// Streams the entire account state at `self.opt.version` out of the backup service, cuts it
+    /// into content-defined chunks, uploads each (skipping ones already present under the same
+    /// digest), and finally writes out a manifest referencing them all. Returns the `FileHandle`
+    /// of the manifest.
+    pub async fn run(self) -> Result<FileHandle> {
+        let version = self.opt.version;
+        let salt = self.load_or_create_salt().await?;
+        let key = self.global_opt.crypto.resolve(salt)?;
+        let key_fingerprint = key.as_ref().map(EncryptionKey::fingerprint);
+        let mut chunker = Chunker::new(&self.global_opt.chunker_opt());
+
+        let mut chunks = Vec::new();
+        let mut pending = Vec::new();
+        let mut pending_first_idx = 0usize;
+        let mut idx = 0usize;
+
+        loop {
+            let page = self
+                .client
+                .get_state_range(version, idx, FETCH_PAGE_SIZE)
+                .await?;
+            if page.is_empty() {
+                break;
+            }
+
+            for record in page {
+                let record_bytes = lcs::to_bytes(&record)?;
+                let is_boundary = chunker.push_record(&record_bytes);
+                pending.push(record);
+                idx += 1;
+
+                if is_boundary {
+                    let finished = std::mem::take(&mut pending);
+                    chunks.push(
+                        self.flush_chunk(&key, key_fingerprint.as_deref(), pending_first_idx, finished)
+                            .await?,
+                    );
+                    pending_first_idx = idx;
+                }
+            }
+        }
+        if !pending.is_empty() {
+            chunks.push(
+                self.flush_chunk(&key, key_fingerprint.as_deref(), pending_first_idx, pending)
+                    .await?,
+            );
+        }
+
+        let (_, state_root_hash) = self.client.get_latest_state_root().await?;
+        ensure!(!chunks.is_empty(), "no account state found at version {}", version);
+
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("system clock is before the epoch")
+            .as_secs();
+        let manifest = StateSnapshotBackupManifest {
+            version,
+            root_hash: state_root_hash,
+            created_at,
+            chunks,
+            crypt_mode: self.global_opt.crypto.crypt_mode,
+            key_fingerprint,
+            key_salt: key.as_ref().and_then(|key| key.salt),
+        };
+        let manifest_bytes = serde_json::to_vec(&manifest)?;
+        let manifest_name = format!("state_snapshot/{}/manifest.json", version);
+        let (manifest_handle, mut writer) = self.storage.create_for_write(&manifest_name).await?;
+        tokio::io::copy(&mut manifest_bytes.as_slice(), &mut writer).await?;
+
+        if let Some(key) = &key {
+            let mac = key.mac(&manifest_bytes);
+            let (_, mut mac_writer) = self.storage.create_for_write(&format!("{}.mac", manifest_name)).await?;
+            tokio::io::copy(&mut mac.as_slice(), &mut mac_writer).await?;
+        }
+
+        if let Some(catalog) = &self.catalog {
+            catalog.record(manifest_handle.clone(), &manifest)?;
+        }
+
+        Ok(manifest_handle)
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! An embedded catalog of every snapshot a `BackupStorage` holds, so restore tooling doesn't have
+//! to scan the whole bucket to find what's available: a small `sled` tree keyed by version (for
+//! listing and `find_by_version`), plus a secondary tree mapping root hash back to version (for
+//! `find_by_root_hash`). Kept up to date transactionally by `StateSnapshotBackupController` and
+//! `PruneController`, and fully reconstructable from the underlying `BackupStorage` with
+//! `Catalog::rebuild`.
+
+use crate::{backup::StateSnapshotBackupManifest, crypto::CryptMode, storage::{BackupStorage, FileHandle}};
+use anyhow::{anyhow, Result};
+use libra_crypto::HashValue;
+use libra_types::transaction::Version;
+use serde::{Deserialize, Serialize};
+use sled::transaction::TransactionError;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+const BY_VERSION_TREE: &str = "snapshots_by_version";
+const BY_ROOT_HASH_TREE: &str = "snapshots_by_root_hash";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub version: Version,
+    pub root_hash: HashValue,
+    pub created_at: u64,
+    pub chunk_count: usize,
+    pub crypt_mode: CryptMode,
+    pub manifest_handle: FileHandle,
+}
+
+impl CatalogEntry {
+    fn from_manifest(manifest_handle: FileHandle, manifest: &StateSnapshotBackupManifest) -> Self {
+        Self {
+            version: manifest.version,
+            root_hash: manifest.root_hash,
+            created_at: manifest.created_at,
+            chunk_count: manifest.chunks.len(),
+            crypt_mode: manifest.crypt_mode,
+            manifest_handle,
+        }
+    }
+}
+
+pub struct Catalog {
+    by_version: sled::Tree,
+    by_root_hash: sled::Tree,
+}
+
+impl Catalog {
+    pub fn open(path: &Path) -> Result<Self> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            by_version: db.open_tree(BY_VERSION_TREE)?,
+            by_root_hash: db.open_tree(BY_ROOT_HASH_TREE)?,
+        })
+    }
+
+    /// Records one snapshot's metadata, keeping the version and root-hash indices in sync with
+    /// each other in a single transaction.
+    pub fn record(&self, manifest_handle: FileHandle, manifest: &StateSnapshotBackupManifest) -> Result<()> {
+        let entry = CatalogEntry::from_manifest(manifest_handle, manifest);
+        let entry_bytes = serde_json::to_vec(&entry)?;
+        let version_key = entry.version.to_be_bytes();
+        let root_hash_key = entry.root_hash.as_ref().to_vec();
+
+        (&self.by_version, &self.by_root_hash)
+            .transaction(|(by_version, by_root_hash)| {
+                by_version.insert(&version_key, entry_bytes.as_slice())?;
+                by_root_hash.insert(root_hash_key.as_slice(), &version_key)?;
+                Ok(())
+            })
+            .map_err(|e: TransactionError<sled::Error>| anyhow!("failed to record catalog entry: {}", e))?;
+        Ok(())
+    }
+
+    /// All known snapshots, oldest first (the natural order of the version-keyed tree).
+    pub fn list(&self) -> Result<Vec<CatalogEntry>> {
+        self.by_version
+            .iter()
+            .values()
+            .map(|bytes| Ok(serde_json::from_slice(&bytes?)?))
+            .collect()
+    }
+
+    pub fn find_by_version(&self, version: Version) -> Result<Option<CatalogEntry>> {
+        match self.by_version.get(version.to_be_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes a snapshot's entry from both indices, e.g. after `PruneController` deletes its
+    /// manifest and chunks from `BackupStorage`. A no-op if `version` isn't present.
+    pub fn remove(&self, version: Version) -> Result<()> {
+        let version_key = version.to_be_bytes();
+        let entry_bytes = match self.by_version.get(&version_key)? {
+            Some(bytes) => bytes,
+            None => return Ok(()),
+        };
+        let entry: CatalogEntry = serde_json::from_slice(&entry_bytes)?;
+        let root_hash_key = entry.root_hash.as_ref().to_vec();
+
+        (&self.by_version, &self.by_root_hash)
+            .transaction(|(by_version, by_root_hash)| {
+                by_version.remove(&version_key)?;
+                by_root_hash.remove(root_hash_key.as_slice())?;
+                Ok(())
+            })
+            .map_err(|e: TransactionError<sled::Error>| anyhow!("failed to remove catalog entry: {}", e))?;
+        Ok(())
+    }
+
+    pub fn find_by_root_hash(&self, root_hash: HashValue) -> Result<Option<CatalogEntry>> {
+        let version_key = match self.by_root_hash.get(root_hash.as_ref())? {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        match self.by_version.get(&version_key)? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Rescans `storage` for manifests and regenerates the catalog at `path` from scratch, for
+    /// when the catalog is lost, corrupted, or just out of sync with the store.
+    pub async fn rebuild(storage: &dyn BackupStorage, path: &Path) -> Result<Self> {
+        let catalog = Self::open(path)?;
+        catalog.by_version.clear()?;
+        catalog.by_root_hash.clear()?;
+
+        for handle in storage.list("state_snapshot").await? {
+            if !handle.ends_with("manifest.json") {
+                continue;
+            }
+            let mut reader = storage.open_for_read(&handle).await?;
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf).await?;
+            let manifest: StateSnapshotBackupManifest = serde_json::from_slice(&buf)?;
+            catalog.record(handle, &manifest)?;
+        }
+
+        Ok(catalog)
+    }
+}
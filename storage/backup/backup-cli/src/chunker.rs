@@ -0,0 +1,100 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Content-defined chunking so that two state snapshots sharing most of their accounts end up
+//! sharing most of their chunks too, instead of re-uploading everything because a fixed-size cut
+//! shifted. A Buzhash rolling hash is run over the serialized account stream and a boundary is
+//! cut whenever the low bits of the hash match a target mask, clamped to `[min_chunk_size,
+//! max_chunk_size]` so no chunk is pathologically tiny or unbounded.
+
+use once_cell::sync::Lazy;
+use std::collections::VecDeque;
+
+const WINDOW_SIZE: usize = 64;
+
+/// A fixed, deterministic byte -> hash table. It must never change between runs: two backups of
+/// overlapping data only produce the same chunk digests if they cut boundaries the same way.
+static BYTE_HASHES: Lazy<[u64; 256]> = Lazy::new(|| {
+    let mut table = [0u64; 256];
+    // splitmix64, seeded with a fixed constant so the table is stable across processes/versions.
+    let mut state = 0x9E3779B97F4A7C15u64;
+    for slot in table.iter_mut() {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        *slot = z ^ (z >> 31);
+    }
+    table
+});
+
+#[derive(Clone, Copy)]
+pub struct ChunkerOpt {
+    pub min_chunk_size: usize,
+    pub avg_chunk_size: usize,
+    pub max_chunk_size: usize,
+}
+
+impl ChunkerOpt {
+    fn mask(&self) -> u64 {
+        let bits = (self.avg_chunk_size.max(2) as f64).log2().round() as u32;
+        (1u64 << bits.min(63)) - 1
+    }
+}
+
+/// Cuts an incoming byte stream into content-defined chunks. Callers feed records in (records,
+/// not raw bytes, matter: the end of each `push_record` call is where it's safe to cut) and get told
+/// after each record whether a chunk boundary falls right after it.
+pub struct Chunker {
+    window: VecDeque<u8>,
+    rolling: u64,
+    mask: u64,
+    min_size: usize,
+    max_size: usize,
+    current_size: usize,
+}
+
+impl Chunker {
+    pub fn new(opt: &ChunkerOpt) -> Self {
+        Self {
+            window: VecDeque::with_capacity(WINDOW_SIZE),
+            rolling: 0,
+            mask: opt.mask(),
+            min_size: opt.min_chunk_size,
+            max_size: opt.max_chunk_size,
+            current_size: 0,
+        }
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        self.current_size += 1;
+        self.rolling = self.rolling.rotate_left(1) ^ BYTE_HASHES[byte as usize];
+        if self.window.len() == WINDOW_SIZE {
+            let leaving = self.window.pop_front().expect("checked non-empty");
+            self.rolling ^= BYTE_HASHES[leaving as usize].rotate_left(WINDOW_SIZE as u32);
+        }
+        self.window.push_back(byte);
+    }
+
+    /// Feeds one record's serialized bytes into the chunker. Returns `true` if a chunk boundary
+    /// should be cut right after this record (never splits a record across chunks). The mask is
+    /// tested at every byte, and a match anywhere in the record moves the cut to its end.
+    pub fn push_record(&mut self, record_bytes: &[u8]) -> bool {
+        let mut mask_hit = false;
+        for &byte in record_bytes {
+            self.push_byte(byte);
+            if self.current_size >= self.min_size && self.rolling & self.mask == 0 {
+                mask_hit = true;
+            }
+        }
+
+        let boundary = self.current_size >= self.max_size || mask_hit;
+
+        if boundary {
+            self.current_size = 0;
+            self.window.clear();
+            self.rolling = 0;
+        }
+        boundary
+    }
+}
@@ -0,0 +1,14 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod backup;
+pub mod catalog;
+pub mod chunker;
+pub mod crypto;
+pub mod prune;
+pub mod restore;
+pub mod storage;
+pub mod verify;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,177 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Enforces a retention policy over accumulated state snapshots: `keep_last` always survives, and
+//! each of `keep_hourly`/`keep_daily`/`keep_weekly`/`keep_monthly`/`keep_yearly` keeps the newest
+//! snapshot in each period it hasn't already kept one for, until its count is exhausted. A
+//! snapshot survives if any category keeps it. Chunks are deduplicated across snapshots, so a
+//! chunk is only actually deleted once no surviving manifest references it anymore.
+
+use crate::backup::{chunk_handle, StateSnapshotBackupManifest};
+use crate::catalog::Catalog;
+use crate::storage::{BackupStorage, FileHandle};
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use std::collections::HashSet;
+use std::sync::Arc;
+use structopt::StructOpt;
+use tokio::io::AsyncReadExt;
+
+#[derive(Clone, StructOpt)]
+pub struct PruneOpt {
+    /// Always keep this many of the most recent snapshots, regardless of age.
+    #[structopt(long, default_value = "0")]
+    pub keep_last: usize,
+    #[structopt(long, default_value = "0")]
+    pub keep_hourly: usize,
+    #[structopt(long, default_value = "0")]
+    pub keep_daily: usize,
+    #[structopt(long, default_value = "0")]
+    pub keep_weekly: usize,
+    #[structopt(long, default_value = "0")]
+    pub keep_monthly: usize,
+    #[structopt(long, default_value = "0")]
+    pub keep_yearly: usize,
+
+    /// List what would be removed without actually deleting anything.
+    #[structopt(long)]
+    pub dry_run: bool,
+}
+
+/// What a (dry-run or real) prune removed. `removed_chunks` pairs each chunk's digest with the
+/// key fingerprint it was stored under (`None` for unencrypted chunks), since that pair is a
+/// chunk's real identity in storage.
+pub struct PruneReport {
+    pub removed_manifests: Vec<FileHandle>,
+    pub removed_chunks: Vec<(String, Option<String>)>,
+}
+
+pub struct PruneController {
+    opt: PruneOpt,
+    storage: Arc<dyn BackupStorage>,
+    catalog: Option<Arc<Catalog>>,
+}
+
+fn period_key(created_at: u64, format: &str) -> String {
+    NaiveDateTime::from_timestamp(created_at as i64, 0)
+        .format(format)
+        .to_string()
+}
+
+impl PruneController {
+    pub fn new(opt: PruneOpt, storage: Arc<dyn BackupStorage>, catalog: Option<Arc<Catalog>>) -> Self {
+        Self {
+            opt,
+            storage,
+            catalog,
+        }
+    }
+
+    async fn load_snapshots(&self) -> Result<Vec<(FileHandle, StateSnapshotBackupManifest)>> {
+        let mut snapshots = Vec::new();
+        for handle in self.storage.list("state_snapshot").await? {
+            if !handle.ends_with("manifest.json") {
+                continue;
+            }
+            let mut reader = self.storage.open_for_read(&handle).await?;
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf).await?;
+            let manifest: StateSnapshotBackupManifest = serde_json::from_slice(&buf)?;
+            snapshots.push((handle, manifest));
+        }
+        snapshots.sort_by(|a, b| b.1.created_at.cmp(&a.1.created_at));
+        Ok(snapshots)
+    }
+
+    /// Runs one category: keeps the newest snapshot in each not-yet-seen period, up to
+    /// `keep_n` periods, marking survivors in `keep`.
+    fn apply_category(
+        snapshots: &[(FileHandle, StateSnapshotBackupManifest)],
+        keep_n: usize,
+        format: &str,
+        keep: &mut [bool],
+    ) {
+        let mut seen_periods = HashSet::new();
+        for (i, (_, manifest)) in snapshots.iter().enumerate() {
+            if seen_periods.len() >= keep_n {
+                break;
+            }
+            if seen_periods.insert(period_key(manifest.created_at, format)) {
+                keep[i] = true;
+            }
+        }
+    }
+
+    pub async fn run(self) -> Result<PruneReport> {
+        let snapshots = self.load_snapshots().await?;
+        let mut keep = vec![false; snapshots.len()];
+
+        for keep_flag in keep.iter_mut().take(self.opt.keep_last) {
+            *keep_flag = true;
+        }
+        Self::apply_category(&snapshots, self.opt.keep_hourly, "%Y-%m-%d-%H", &mut keep);
+        Self::apply_category(&snapshots, self.opt.keep_daily, "%Y-%m-%d", &mut keep);
+        Self::apply_category(&snapshots, self.opt.keep_weekly, "%G-W%V", &mut keep);
+        Self::apply_category(&snapshots, self.opt.keep_monthly, "%Y-%m", &mut keep);
+        Self::apply_category(&snapshots, self.opt.keep_yearly, "%Y", &mut keep);
+
+        // Chunk storage is scoped by (digest, key_fingerprint) (see `chunk_handle`), so that's
+        // also the identity a chunk needs to be deduplicated/retained by here.
+        let kept_chunks: HashSet<(&str, Option<&str>)> = snapshots
+            .iter()
+            .zip(&keep)
+            .filter(|(_, &kept)| kept)
+            .flat_map(|((_, manifest), _)| {
+                manifest
+                    .chunks
+                    .iter()
+                    .map(move |chunk| (chunk.digest.as_str(), manifest.key_fingerprint.as_deref()))
+            })
+            .collect();
+
+        let mut removed_manifests = Vec::new();
+        let mut removed_versions = Vec::new();
+        let mut candidate_chunks = HashSet::new();
+        for ((handle, manifest), &kept) in snapshots.iter().zip(&keep) {
+            if kept {
+                continue;
+            }
+            removed_manifests.push(handle.clone());
+            removed_versions.push(manifest.version);
+            candidate_chunks.extend(
+                manifest
+                    .chunks
+                    .iter()
+                    .map(|chunk| (chunk.digest.clone(), manifest.key_fingerprint.clone())),
+            );
+        }
+        let removed_chunks: Vec<(String, Option<String>)> = candidate_chunks
+            .into_iter()
+            .filter(|(digest, fingerprint)| {
+                !kept_chunks.contains(&(digest.as_str(), fingerprint.as_deref()))
+            })
+            .collect();
+
+        if !self.opt.dry_run {
+            for handle in &removed_manifests {
+                self.storage.delete(handle).await?;
+                self.storage.delete(&format!("{}.mac", handle)).await?;
+            }
+            for (digest, fingerprint) in &removed_chunks {
+                self.storage
+                    .delete(&chunk_handle(digest, fingerprint.as_deref()))
+                    .await?;
+            }
+            if let Some(catalog) = &self.catalog {
+                for version in &removed_versions {
+                    catalog.remove(*version)?;
+                }
+            }
+        }
+
+        Ok(PruneReport {
+            removed_manifests,
+            removed_chunks,
+        })
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Drives restoring a state snapshot previously produced by `StateSnapshotBackupController` onto
+//! a target `LibraDB`: reads the manifest, streams each chunk back in order, and feeds the leaves
+//! into the DB's Merkle restore handle, which verifies the resulting root hash matches the one
+//! recorded in the manifest as it goes.
+
+use crate::{
+    backup::{chunk_handle, StateSnapshotBackupManifest, StateSnapshotChunkContent},
+    crypto::{ensure_crypt_mode_matches, CryptMode, CryptoOpt},
+    storage::{BackupStorage, FileHandle},
+};
+use anyhow::{ensure, Result};
+use libra_types::transaction::Version;
+use sha2::Digest;
+use std::sync::Arc;
+use structopt::StructOpt;
+use tokio::io::AsyncReadExt;
+
+#[derive(Clone, StructOpt)]
+pub struct StateSnapshotRestoreOpt {
+    /// The `FileHandle` of the manifest produced by the backup to restore.
+    #[structopt(long)]
+    pub manifest_handle: FileHandle,
+
+    /// The version the restored state should be recorded under in the target DB.
+    #[structopt(long)]
+    pub version: Version,
+
+    #[structopt(flatten)]
+    pub crypto: CryptoOpt,
+}
+
+pub struct StateSnapshotRestoreController {
+    opt: StateSnapshotRestoreOpt,
+    storage: Arc<dyn BackupStorage>,
+    db: Arc<libradb::LibraDB>,
+}
+
+impl StateSnapshotRestoreController {
+    pub fn new(
+        opt: StateSnapshotRestoreOpt,
+        storage: Arc<dyn BackupStorage>,
+        db: Arc<libradb::LibraDB>,
+    ) -> Self {
+        Self { opt, storage, db }
+    }
+
+    async fn read_file(&self, file_handle: &FileHandle) -> Result<Vec<u8>> {
+        let mut reader = self.storage.open_for_read(file_handle).await?;
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await?;
+        Ok(buf)
+    }
+
+    /// Reads the manifest and every chunk it references, feeding accounts into the target DB's
+    /// Merkle restore handle in order, then asserts the reconstructed root hash matches what the
+    /// manifest recorded before this controller was asked to run.
+    pub async fn run(self) -> Result<()> {
+        let manifest_bytes = self.read_file(&self.opt.manifest_handle).await?;
+        let manifest: StateSnapshotBackupManifest = serde_json::from_slice(&manifest_bytes)?;
+        ensure_crypt_mode_matches(self.opt.crypto.crypt_mode, manifest.crypt_mode)?;
+
+        let key = match manifest.crypt_mode {
+            CryptMode::None => None,
+            CryptMode::Encrypt => {
+                let key = self
+                    .opt
+                    .crypto
+                    .resolve(manifest.key_salt)?
+                    .ok_or_else(|| anyhow::anyhow!("manifest was encrypted but no key was supplied"))?;
+                ensure!(
+                    Some(key.fingerprint()) == manifest.key_fingerprint,
+                    "supplied key does not match the key this backup was encrypted with"
+                );
+                let mac_bytes = self.read_file(&format!("{}.mac", self.opt.manifest_handle)).await?;
+                key.verify_mac(&manifest_bytes, &mac_bytes)?;
+                Some(key)
+            }
+        };
+
+        let mut restore = self
+            .db
+            .get_restore_handler()
+            .get_state_restore_receiver(self.opt.version, manifest.root_hash)?;
+
+        for chunk in &manifest.chunks {
+            let chunk_bytes = self
+                .read_file(&chunk_handle(&chunk.digest, manifest.key_fingerprint.as_deref()))
+                .await?;
+            let chunk_bytes = match &key {
+                Some(key) => key.decrypt(&chunk_bytes)?,
+                None => chunk_bytes,
+            };
+            ensure!(
+                hex::encode(sha2::Sha256::digest(&chunk_bytes)) == chunk.digest,
+                "chunk content does not match its digest for range [{}, {}]",
+                chunk.first_idx,
+                chunk.last_idx,
+            );
+            let content: StateSnapshotChunkContent = lcs::from_bytes(&chunk_bytes)?;
+            ensure!(
+                content.blobs.first().map(|(key, _)| *key) == Some(chunk.first_key)
+                    && content.blobs.last().map(|(key, _)| *key) == Some(chunk.last_key),
+                "chunk content does not match manifest for range [{}, {}]",
+                chunk.first_idx,
+                chunk.last_idx,
+            );
+            restore.add_chunk(content.blobs, chunk.proof.clone())?;
+        }
+
+        restore.finish()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! A `BackupStorage` that keeps everything on the local filesystem, rooted at a directory
+//! configured at construction time. This is what the `end_to_end` test uses, and is a reasonable
+//! default for operators who back up to an already-mounted network volume.
+
+use crate::storage::{BackupStorage, FileHandle};
+use anyhow::{ensure, Result};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub struct LocalFs {
+    dir: PathBuf,
+}
+
+impl LocalFs {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn file_path(&self, file_handle: &FileHandle) -> PathBuf {
+        self.dir.join(file_handle)
+    }
+}
+
+#[async_trait]
+impl BackupStorage for LocalFs {
+    async fn create_for_write(
+        &self,
+        file_name: &str,
+    ) -> Result<(FileHandle, Box<dyn AsyncWrite + Send + Unpin>)> {
+        let file_handle = file_name.to_string();
+        let path = self.file_path(&file_handle);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+        Ok((file_handle, Box::new(file)))
+    }
+
+    async fn open_for_read(&self, file_handle: &FileHandle) -> Result<Box<dyn AsyncRead + Send + Unpin>> {
+        let path = self.file_path(file_handle);
+        ensure!(path.is_file(), "file does not exist: {:?}", path);
+        let file = File::open(&path).await?;
+        Ok(Box::new(file))
+    }
+
+    async fn exists(&self, file_handle: &FileHandle) -> Result<bool> {
+        Ok(self.file_path(file_handle).is_file())
+    }
+
+    async fn list(&self, dir: &str) -> Result<Vec<FileHandle>> {
+        let root = self.dir.join(dir);
+        if !root.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut handles = Vec::new();
+        let mut stack = vec![root.clone()];
+        while let Some(path) = stack.pop() {
+            for entry in std::fs::read_dir(&path)? {
+                let entry = entry?;
+                let entry_path = entry.path();
+                if entry_path.is_dir() {
+                    stack.push(entry_path);
+                } else {
+                    let relative = entry_path.strip_prefix(&self.dir)?;
+                    handles.push(relative.to_string_lossy().into_owned());
+                }
+            }
+        }
+        Ok(handles)
+    }
+
+    async fn delete(&self, file_handle: &FileHandle) -> Result<()> {
+        let path = self.file_path(file_handle);
+        if path.is_file() {
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+}
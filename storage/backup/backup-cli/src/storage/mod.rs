@@ -0,0 +1,46 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module defines the `BackupStorage` trait, the abstraction all backup tooling is built
+//! on top of. Concrete backends (local filesystem, remote object stores, etc) live in
+//! submodules and only need to implement streaming reads and writes keyed by opaque handles.
+
+pub mod local_fs;
+pub mod s3;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// An opaque handle referring to a file a `BackupStorage` knows how to open for read. It is
+/// produced by `create_for_write` and is what callers persist (e.g. in a manifest) in order to
+/// read the file back later.
+pub type FileHandle = String;
+
+/// A `BackupStorage` is a place `backup-cli` can write backup files (chunks, manifests) to and
+/// read them back from, with no assumptions on how it's physically organized. Implementations
+/// are free to lay files out however best suits the backing store.
+#[async_trait]
+pub trait BackupStorage: Send + Sync {
+    /// Opens a new file for write and returns a `FileHandle` that can be used to read it back,
+    /// together with the writer itself.
+    async fn create_for_write(
+        &self,
+        file_name: &str,
+    ) -> Result<(FileHandle, Box<dyn AsyncWrite + Send + Unpin>)>;
+
+    /// Opens an existing file, referred to by a `FileHandle` previously returned by
+    /// `create_for_write`, for read.
+    async fn open_for_read(&self, file_handle: &FileHandle) -> Result<Box<dyn AsyncRead + Send + Unpin>>;
+
+    /// Returns whether a file already exists under `file_handle`, so callers can skip
+    /// re-uploading content-addressed chunks that are already present.
+    async fn exists(&self, file_handle: &FileHandle) -> Result<bool>;
+
+    /// Lists every `FileHandle` stored under `dir`, so tooling (e.g. prune) can discover what's
+    /// in a store without having to be told about it up front.
+    async fn list(&self, dir: &str) -> Result<Vec<FileHandle>>;
+
+    /// Deletes the file referred to by `file_handle`. A no-op if it doesn't exist.
+    async fn delete(&self, file_handle: &FileHandle) -> Result<()>;
+}
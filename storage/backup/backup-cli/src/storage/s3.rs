@@ -0,0 +1,343 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! A `BackupStorage` backed by an S3-compatible object store, so operators can keep snapshots
+//! off-box for disaster recovery. Modeled on the "repository" abstraction used by backup clients
+//! like proxmox-backup: a bucket plus an optional key prefix, configured once, that chunks and
+//! manifests are addressed under by name. Uploads go through S3 multipart, so only about one part
+//! (`part_size` bytes) of a file is buffered at a time; a file no larger than that is buffered in
+//! full and sent as a single part.
+
+use crate::storage::{BackupStorage, FileHandle};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use rusoto_core::RusotoError;
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateMultipartUploadRequest, DeleteObjectRequest, GetObjectRequest,
+    HeadObjectError, HeadObjectRequest, ListObjectsV2Request, PutObjectRequest, S3Client,
+    UploadPartRequest, S3,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot};
+
+/// Minimum part size S3 will accept for all but the last part of a multipart upload.
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+pub struct S3BackupStorage {
+    client: S3Client,
+    bucket: String,
+    prefix: Option<String>,
+    part_size: usize,
+}
+
+impl S3BackupStorage {
+    /// `part_size` is typically set to `GlobalBackupOpt::max_chunk_size`, so each chunk file
+    /// backup-cli writes lines up with one (or a handful of) S3 multipart parts.
+    pub fn new(client: S3Client, bucket: String, prefix: Option<String>, part_size: usize) -> Self {
+        Self {
+            client,
+            bucket,
+            prefix,
+            part_size: part_size.max(MIN_PART_SIZE),
+        }
+    }
+
+    pub(crate) fn key(&self, file_name: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{}/{}", prefix, file_name),
+            None => file_name.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl BackupStorage for S3BackupStorage {
+    async fn create_for_write(
+        &self,
+        file_name: &str,
+    ) -> Result<(FileHandle, Box<dyn AsyncWrite + Send + Unpin>)> {
+        let key = self.key(file_name);
+        let upload = self
+            .client
+            .create_multipart_upload(CreateMultipartUploadRequest {
+                bucket: self.bucket.clone(),
+                key: key.clone(),
+                ..Default::default()
+            })
+            .await?;
+        let upload_id = upload
+            .upload_id
+            .ok_or_else(|| anyhow!("S3 did not return an upload id for {}", key))?;
+
+        let writer = MultipartUploadWriter::new(self.client.clone(), self.bucket.clone(), key.clone(), upload_id, self.part_size);
+        Ok((key, Box::new(writer)))
+    }
+
+    async fn open_for_read(&self, file_handle: &FileHandle) -> Result<Box<dyn AsyncRead + Send + Unpin>> {
+        let object = self
+            .client
+            .get_object(GetObjectRequest {
+                bucket: self.bucket.clone(),
+                key: file_handle.clone(),
+                ..Default::default()
+            })
+            .await?;
+        let body = object
+            .body
+            .ok_or_else(|| anyhow!("S3 object has no body: {}", file_handle))?;
+        Ok(Box::new(tokio_util::io::StreamReader::new(body)))
+    }
+
+    async fn exists(&self, file_handle: &FileHandle) -> Result<bool> {
+        let result = self
+            .client
+            .head_object(HeadObjectRequest {
+                bucket: self.bucket.clone(),
+                key: file_handle.clone(),
+                ..Default::default()
+            })
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(false),
+            Err(RusotoError::Unknown(response)) if response.status.as_u16() == 404 => Ok(false),
+            Err(e) => Err(anyhow!(e)),
+        }
+    }
+
+    async fn list(&self, dir: &str) -> Result<Vec<FileHandle>> {
+        let prefix = self.key(dir);
+        let mut handles = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let output = self
+                .client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.bucket.clone(),
+                    prefix: Some(prefix.clone()),
+                    continuation_token: continuation_token.clone(),
+                    ..Default::default()
+                })
+                .await?;
+            handles.extend(
+                output
+                    .contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|object| object.key),
+            );
+            continuation_token = output.next_continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        Ok(handles)
+    }
+
+    async fn delete(&self, file_handle: &FileHandle) -> Result<()> {
+        self.client
+            .delete_object(DeleteObjectRequest {
+                bucket: self.bucket.clone(),
+                key: file_handle.clone(),
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+/// The buffering/part-numbering half of `MultipartUploadWriter`, kept free of any S3 client so it
+/// can be unit tested without a network round trip: buffers writes up to `part_size` and, once
+/// crossed, hands back one full part with the next sequential part number (S3 part numbers start
+/// at 1 and must be assigned in order).
+pub(crate) struct PartBuffer {
+    buffer: Vec<u8>,
+    part_size: usize,
+    next_part_number: i64,
+}
+
+impl PartBuffer {
+    pub(crate) fn new(part_size: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            part_size,
+            next_part_number: 1,
+        }
+    }
+
+    /// Appends `data`, returning a part to upload if the buffer has reached `part_size`.
+    pub(crate) fn push(&mut self, data: &[u8]) -> Option<(i64, Bytes)> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= self.part_size {
+            self.flush()
+        } else {
+            None
+        }
+    }
+
+    /// Flushes whatever is currently buffered as one final, possibly-undersized part. Returns
+    /// `None` if nothing has been buffered since the last flush, so callers don't upload an empty
+    /// part.
+    pub(crate) fn flush(&mut self) -> Option<(i64, Bytes)> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let bytes = Bytes::from(std::mem::take(&mut self.buffer));
+        let part_number = self.next_part_number;
+        self.next_part_number += 1;
+        Some((part_number, bytes))
+    }
+}
+
+/// Buffers writes up to `part_size`, uploading each full buffer as its own S3 multipart part on
+/// a background task so the caller isn't blocked on the network between parts. The upload is
+/// completed (or, on error, aborted) when the writer is flushed.
+struct MultipartUploadWriter {
+    parts: PartBuffer,
+    to_task: mpsc::UnboundedSender<TaskMsg>,
+    done: Option<oneshot::Receiver<Result<()>>>,
+}
+
+enum TaskMsg {
+    Part(i64, Bytes),
+    Finish,
+}
+
+impl MultipartUploadWriter {
+    fn new(client: S3Client, bucket: String, key: String, upload_id: String, part_size: usize) -> Self {
+        let (to_task, mut from_writer) = mpsc::unbounded_channel::<TaskMsg>();
+        let (done_tx, done_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let mut completed_parts = Vec::new();
+            let result: Result<()> = async {
+                while let Some(msg) = from_writer.recv().await {
+                    match msg {
+                        TaskMsg::Part(part_number, bytes) => {
+                            let len = bytes.len() as i64;
+                            let output = client
+                                .upload_part(UploadPartRequest {
+                                    bucket: bucket.clone(),
+                                    key: key.clone(),
+                                    upload_id: upload_id.clone(),
+                                    part_number,
+                                    body: Some(bytes.to_vec().into()),
+                                    content_length: Some(len),
+                                    ..Default::default()
+                                })
+                                .await?;
+                            let e_tag = output
+                                .e_tag
+                                .ok_or_else(|| anyhow!("S3 did not return an ETag for part {}", part_number))?;
+                            completed_parts.push(CompletedPart {
+                                e_tag: Some(e_tag),
+                                part_number: Some(part_number),
+                            });
+                        }
+                        TaskMsg::Finish => {
+                            if completed_parts.is_empty() {
+                                // Nothing was ever written (e.g. a zero-byte file): no part was
+                                // ever uploaded, and S3 rejects completing a multipart upload
+                                // with zero parts. Abort the multipart upload that was never
+                                // used and write the (empty) object directly instead.
+                                client
+                                    .abort_multipart_upload(AbortMultipartUploadRequest {
+                                        bucket: bucket.clone(),
+                                        key: key.clone(),
+                                        upload_id: upload_id.clone(),
+                                        ..Default::default()
+                                    })
+                                    .await?;
+                                client
+                                    .put_object(PutObjectRequest {
+                                        bucket: bucket.clone(),
+                                        key: key.clone(),
+                                        body: Some(Vec::new().into()),
+                                        ..Default::default()
+                                    })
+                                    .await?;
+                            } else {
+                                client
+                                    .complete_multipart_upload(CompleteMultipartUploadRequest {
+                                        bucket: bucket.clone(),
+                                        key: key.clone(),
+                                        upload_id: upload_id.clone(),
+                                        multipart_upload: Some(CompletedMultipartUpload {
+                                            parts: Some(completed_parts.clone()),
+                                        }),
+                                        ..Default::default()
+                                    })
+                                    .await?;
+                            }
+                            return Ok(());
+                        }
+                    }
+                }
+                Ok(())
+            }
+            .await;
+
+            if result.is_err() {
+                let _ = client
+                    .abort_multipart_upload(AbortMultipartUploadRequest {
+                        bucket: bucket.clone(),
+                        key: key.clone(),
+                        upload_id: upload_id.clone(),
+                        ..Default::default()
+                    })
+                    .await;
+            }
+            let _ = done_tx.send(result);
+        });
+
+        Self {
+            parts: PartBuffer::new(part_size),
+            to_task,
+            done: Some(done_rx),
+        }
+    }
+
+    fn send(&self, part_number: i64, bytes: Bytes) -> std::io::Result<()> {
+        self.to_task
+            .send(TaskMsg::Part(part_number, bytes))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::BrokenPipe, e.to_string()))
+    }
+}
+
+impl AsyncWrite for MultipartUploadWriter {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if let Some((part_number, bytes)) = this.parts.push(buf) {
+            this.send(part_number, bytes)?;
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if let Some((part_number, bytes)) = this.parts.flush() {
+            this.send(part_number, bytes)?;
+        }
+        if this.to_task.send(TaskMsg::Finish).is_err() {
+            return Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "upload task gone")));
+        }
+        let done = this
+            .done
+            .as_mut()
+            .expect("poll_shutdown called more than once");
+        match Pin::new(done).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "upload task dropped"))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
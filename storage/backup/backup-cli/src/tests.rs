@@ -3,20 +3,79 @@
 
 use crate::{
     backup::{
-        BackupServiceClient, GlobalBackupOpt, StateSnapshotBackupController, StateSnapshotBackupOpt,
+        chunk_handle, BackupServiceClient, GlobalBackupOpt, StateSnapshotBackupController,
+        StateSnapshotBackupManifest, StateSnapshotBackupOpt, StateSnapshotChunk,
     },
+    catalog::Catalog,
+    crypto::{CryptMode, CryptoOpt},
+    prune::{PruneController, PruneOpt, PruneReport},
     restore::{StateSnapshotRestoreController, StateSnapshotRestoreOpt},
-    storage::{local_fs::LocalFs, BackupStorage},
+    storage::{local_fs::LocalFs, s3::{PartBuffer, S3BackupStorage}, BackupStorage, FileHandle},
+    verify::{StateSnapshotVerifyController, StateSnapshotVerifyOpt},
 };
 use backup_service::start_backup_service;
 use libra_config::config::NodeConfig;
+use libra_crypto::HashValue;
 use libra_proptest_helpers::ValueGenerator;
 use libra_temppath::TempPath;
-use libra_types::transaction::PRE_GENESIS_VERSION;
+use libra_types::proof::SparseMerkleRangeProof;
+use libra_types::transaction::{Version, PRE_GENESIS_VERSION};
 use libradb::{test_helper::arb_blocks_to_commit, LibraDB};
+use rusoto_core::Region;
+use rusoto_s3::S3Client;
+use std::collections::HashSet;
 use std::sync::Arc;
 use storage_interface::{DbReader, DbWriter};
 
+/// Builds a manifest directly (no real backup run) for controllers that only care about
+/// manifest/catalog bookkeeping (`prune`, `catalog`), not actual chunk content.
+fn synthetic_manifest(version: Version, created_at: u64, digests: &[&str]) -> StateSnapshotBackupManifest {
+    StateSnapshotBackupManifest {
+        version,
+        root_hash: HashValue::sha3_256_of(format!("root-{}", version).as_bytes()),
+        created_at,
+        chunks: digests
+            .iter()
+            .map(|digest| StateSnapshotChunk {
+                first_idx: 0,
+                last_idx: 0,
+                first_key: HashValue::zero(),
+                last_key: HashValue::zero(),
+                digest: (*digest).to_string(),
+                proof: SparseMerkleRangeProof::new(vec![]),
+            })
+            .collect(),
+        crypt_mode: CryptMode::None,
+        key_fingerprint: None,
+        key_salt: None,
+    }
+}
+
+/// Writes `manifest` and a placeholder file for each of its chunks directly into `store`, as if a
+/// real backup had produced them, and returns the manifest's `FileHandle`.
+fn write_manifest(
+    rt: &mut tokio::runtime::Runtime,
+    store: &Arc<dyn BackupStorage>,
+    manifest: &StateSnapshotBackupManifest,
+) -> FileHandle {
+    let manifest_bytes = serde_json::to_vec(manifest).unwrap();
+    let manifest_name = format!("state_snapshot/{}/manifest.json", manifest.version);
+    let (handle, mut writer) = rt.block_on(store.create_for_write(&manifest_name)).unwrap();
+    rt.block_on(tokio::io::copy(&mut manifest_bytes.as_slice(), &mut writer))
+        .unwrap();
+    for chunk in &manifest.chunks {
+        let (_, mut chunk_writer) = rt
+            .block_on(store.create_for_write(&chunk_handle(
+                &chunk.digest,
+                manifest.key_fingerprint.as_deref(),
+            )))
+            .unwrap();
+        rt.block_on(tokio::io::copy(&mut b"x".as_ref(), &mut chunk_writer))
+            .unwrap();
+    }
+    handle
+}
+
 fn tmp_db_empty() -> (TempPath, Arc<LibraDB>) {
     let tmpdir = TempPath::new();
     let db = Arc::new(LibraDB::new_for_test(&tmpdir));
@@ -24,16 +83,19 @@ fn tmp_db_empty() -> (TempPath, Arc<LibraDB>) {
     (tmpdir, db)
 }
 
-fn tmp_db_with_random_content() -> (TempPath, Arc<LibraDB>) {
+fn tmp_db_with_blocks(
+    blocks: &[(
+        Vec<libra_types::transaction::TransactionToCommit>,
+        libra_types::ledger_info::LedgerInfoWithSignatures,
+    )],
+) -> (TempPath, Arc<LibraDB>) {
     let (tmpdir, db) = tmp_db_empty();
     let mut cur_ver = 0;
-    for (txns_to_commit, ledger_info_with_sigs) in
-        ValueGenerator::new().generate(arb_blocks_to_commit())
-    {
+    for (txns_to_commit, ledger_info_with_sigs) in blocks {
         db.save_transactions(
-            &txns_to_commit,
+            txns_to_commit,
             cur_ver, /* first_version */
-            Some(&ledger_info_with_sigs),
+            Some(ledger_info_with_sigs),
         )
         .unwrap();
         cur_ver += txns_to_commit.len() as u64;
@@ -42,6 +104,10 @@ fn tmp_db_with_random_content() -> (TempPath, Arc<LibraDB>) {
     (tmpdir, db)
 }
 
+fn tmp_db_with_random_content() -> (TempPath, Arc<LibraDB>) {
+    tmp_db_with_blocks(&ValueGenerator::new().generate(arb_blocks_to_commit()))
+}
+
 #[test]
 fn end_to_end() {
     let (_src_db_dir, src_db) = tmp_db_with_random_content();
@@ -59,10 +125,14 @@ fn end_to_end() {
             StateSnapshotBackupController::new(
                 StateSnapshotBackupOpt { version },
                 GlobalBackupOpt {
+                    avg_chunk_size: 64,
+                    min_chunk_size: 16,
                     max_chunk_size: 500,
+                    crypto: CryptoOpt::default(),
                 },
                 client,
                 Arc::clone(&store),
+                None,
             )
             .run(),
         )
@@ -73,6 +143,7 @@ fn end_to_end() {
             StateSnapshotRestoreOpt {
                 manifest_handle,
                 version: PRE_GENESIS_VERSION,
+                crypto: CryptoOpt::default(),
             },
             store,
             Arc::clone(&tgt_db),
@@ -88,3 +159,650 @@ fn end_to_end() {
         state_root_hash,
     );
 }
+
+fn chunk_file_count(backup_dir: &TempPath) -> usize {
+    std::fs::read_dir(backup_dir.path().join("chunks"))
+        .map(|entries| entries.count())
+        .unwrap_or(0)
+}
+
+/// Two snapshots of the same evolving DB share most of their account state. Backing both of them
+/// up should reuse the first snapshot's chunks for the part of the state they have in common,
+/// instead of re-uploading everything.
+#[test]
+fn dedups_chunks_across_overlapping_snapshots() {
+    let blocks = ValueGenerator::new().generate(arb_blocks_to_commit());
+    let prefix_len = (blocks.len() / 2).max(1);
+
+    let (_db1_dir, db1) = tmp_db_with_blocks(&blocks[..prefix_len]);
+    let (_db2_dir, db2) = tmp_db_with_blocks(&blocks);
+
+    let backup_dir = TempPath::new();
+    backup_dir.create_as_dir().unwrap();
+    let store: Arc<dyn BackupStorage> = Arc::new(LocalFs::new(backup_dir.path().to_path_buf()));
+
+    let global_opt = || GlobalBackupOpt {
+        avg_chunk_size: 64,
+        min_chunk_size: 16,
+        max_chunk_size: 500,
+        crypto: CryptoOpt::default(),
+    };
+
+    let config1 = NodeConfig::random();
+    let mut rt1 = start_backup_service(config1.storage.backup_service_port, db1);
+    let client1 = Arc::new(BackupServiceClient::new(config1.storage.backup_service_port));
+    let (version1, _) = rt1.block_on(client1.get_latest_state_root()).unwrap();
+    rt1.block_on(
+        StateSnapshotBackupController::new(
+            StateSnapshotBackupOpt { version: version1 },
+            global_opt(),
+            client1,
+            Arc::clone(&store),
+            None,
+        )
+        .run(),
+    )
+    .unwrap();
+    let chunks_after_first_backup = chunk_file_count(&backup_dir);
+    assert!(chunks_after_first_backup > 0);
+
+    let config2 = NodeConfig::random();
+    let mut rt2 = start_backup_service(config2.storage.backup_service_port, db2);
+    let client2 = Arc::new(BackupServiceClient::new(config2.storage.backup_service_port));
+    let (version2, _) = rt2.block_on(client2.get_latest_state_root()).unwrap();
+    let manifest2 = rt2
+        .block_on(
+            StateSnapshotBackupController::new(
+                StateSnapshotBackupOpt { version: version2 },
+                global_opt(),
+                client2,
+                Arc::clone(&store),
+                None,
+            )
+            .run(),
+        )
+        .unwrap();
+    let _ = manifest2;
+
+    let chunks_after_second_backup = chunk_file_count(&backup_dir);
+    // If the second snapshot re-uploaded everything, the chunk count would grow by roughly the
+    // first snapshot's count again; deduplication means it grows by strictly less than that.
+    assert!(chunks_after_second_backup < chunks_after_first_backup * 2);
+}
+
+/// Same scenario as `dedups_chunks_across_overlapping_snapshots`, but with `--passphrase`
+/// encryption on. The two backups derive their key from the same passphrase, so the chunks the
+/// second backup reuses from the first must actually be decryptable with the key the second
+/// backup's manifest points a restore at, not just coincidentally reused by digest.
+#[test]
+fn dedups_encrypted_chunks_across_overlapping_snapshots_and_restores() {
+    let blocks = ValueGenerator::new().generate(arb_blocks_to_commit());
+    let prefix_len = (blocks.len() / 2).max(1);
+
+    let (_db1_dir, db1) = tmp_db_with_blocks(&blocks[..prefix_len]);
+    let (_db2_dir, db2) = tmp_db_with_blocks(&blocks);
+    let (_tgt_db_dir, tgt_db) = tmp_db_empty();
+
+    let backup_dir = TempPath::new();
+    backup_dir.create_as_dir().unwrap();
+    let store: Arc<dyn BackupStorage> = Arc::new(LocalFs::new(backup_dir.path().to_path_buf()));
+
+    let global_opt = || GlobalBackupOpt {
+        avg_chunk_size: 64,
+        min_chunk_size: 16,
+        max_chunk_size: 500,
+        crypto: CryptoOpt {
+            crypt_mode: CryptMode::Encrypt,
+            key_file: None,
+            passphrase: Some("correct horse battery staple".to_string()),
+        },
+    };
+
+    let config1 = NodeConfig::random();
+    let mut rt1 = start_backup_service(config1.storage.backup_service_port, db1);
+    let client1 = Arc::new(BackupServiceClient::new(config1.storage.backup_service_port));
+    let (version1, _) = rt1.block_on(client1.get_latest_state_root()).unwrap();
+    rt1.block_on(
+        StateSnapshotBackupController::new(
+            StateSnapshotBackupOpt { version: version1 },
+            global_opt(),
+            client1,
+            Arc::clone(&store),
+            None,
+        )
+        .run(),
+    )
+    .unwrap();
+
+    let config2 = NodeConfig::random();
+    let mut rt2 = start_backup_service(config2.storage.backup_service_port, db2);
+    let client2 = Arc::new(BackupServiceClient::new(config2.storage.backup_service_port));
+    let (version2, state_root_hash2) = rt2.block_on(client2.get_latest_state_root()).unwrap();
+    let manifest2 = rt2
+        .block_on(
+            StateSnapshotBackupController::new(
+                StateSnapshotBackupOpt { version: version2 },
+                global_opt(),
+                client2,
+                Arc::clone(&store),
+                None,
+            )
+            .run(),
+        )
+        .unwrap();
+
+    // If the two runs had derived different keys (e.g. a fresh random salt per run), restoring
+    // manifest2 would try to AEAD-decrypt chunks reused from the first run under the wrong key
+    // and fail outright instead of reconstructing the state.
+    rt2.block_on(
+        StateSnapshotRestoreController::new(
+            StateSnapshotRestoreOpt {
+                manifest_handle: manifest2,
+                version: PRE_GENESIS_VERSION,
+                crypto: global_opt().crypto,
+            },
+            store,
+            Arc::clone(&tgt_db),
+        )
+        .run(),
+    )
+    .unwrap();
+    assert_eq!(
+        tgt_db
+            .get_latest_tree_state()
+            .unwrap()
+            .account_state_root_hash,
+        state_root_hash2,
+    );
+}
+
+/// Five daily snapshots, the last two of which share a chunk. `--keep-last 1 --keep-daily 2`
+/// should keep only the two most recent, a dry run should report that without touching anything,
+/// and a real run should delete only the chunks no surviving manifest references anymore.
+#[test]
+fn prune_keeps_by_bucket_and_removes_dangling_chunks_only() {
+    const DAY: u64 = 86_400;
+    const BASE: u64 = 1_700_000_000;
+
+    let backup_dir = TempPath::new();
+    backup_dir.create_as_dir().unwrap();
+    let store: Arc<dyn BackupStorage> = Arc::new(LocalFs::new(backup_dir.path().to_path_buf()));
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+    let catalog_dir = TempPath::new();
+    catalog_dir.create_as_dir().unwrap();
+    let catalog = Arc::new(Catalog::open(catalog_dir.path()).unwrap());
+
+    let manifests = vec![
+        synthetic_manifest(1, BASE, &["d1"]),
+        synthetic_manifest(2, BASE + DAY, &["d2"]),
+        synthetic_manifest(3, BASE + 2 * DAY, &["d3"]),
+        synthetic_manifest(4, BASE + 3 * DAY, &["d4a", "dshared"]),
+        synthetic_manifest(5, BASE + 4 * DAY, &["d5a", "dshared"]),
+    ];
+    for manifest in &manifests {
+        let handle = write_manifest(&mut rt, &store, manifest);
+        catalog.record(handle, manifest).unwrap();
+    }
+
+    let opt = |dry_run: bool| PruneOpt {
+        keep_last: 1,
+        keep_hourly: 0,
+        keep_daily: 2,
+        keep_weekly: 0,
+        keep_monthly: 0,
+        keep_yearly: 0,
+        dry_run,
+    };
+    let removed_digests = |report: &PruneReport| -> HashSet<&str> {
+        report
+            .removed_chunks
+            .iter()
+            .map(|(digest, _)| digest.as_str())
+            .collect()
+    };
+
+    let dry_report = rt
+        .block_on(
+            PruneController::new(opt(true), Arc::clone(&store), Some(Arc::clone(&catalog))).run(),
+        )
+        .unwrap();
+    assert_eq!(dry_report.removed_manifests.len(), 3);
+    assert_eq!(
+        removed_digests(&dry_report),
+        ["d1", "d2", "d3"].iter().copied().collect()
+    );
+    // A dry run must not touch storage or the catalog.
+    assert!(rt.block_on(store.exists(&chunk_handle("d1", None))).unwrap());
+    assert!(catalog.find_by_version(1).unwrap().is_some());
+
+    let report = rt
+        .block_on(
+            PruneController::new(opt(false), Arc::clone(&store), Some(Arc::clone(&catalog))).run(),
+        )
+        .unwrap();
+    assert_eq!(report.removed_manifests.len(), 3);
+    assert_eq!(
+        removed_digests(&report),
+        ["d1", "d2", "d3"].iter().copied().collect()
+    );
+
+    for digest in ["d1", "d2", "d3"] {
+        assert!(!rt.block_on(store.exists(&chunk_handle(digest, None))).unwrap());
+    }
+    for version in [1, 2, 3] {
+        assert!(catalog.find_by_version(version).unwrap().is_none());
+    }
+
+    // v4 and v5 survive (keep_last + keep_daily), so the chunk they share must not be deleted.
+    for digest in ["d4a", "d5a", "dshared"] {
+        assert!(rt.block_on(store.exists(&chunk_handle(digest, None))).unwrap());
+    }
+    for version in [4, 5] {
+        assert!(catalog.find_by_version(version).unwrap().is_some());
+    }
+}
+
+#[test]
+fn catalog_records_lists_finds_removes_and_rebuilds() {
+    let backup_dir = TempPath::new();
+    backup_dir.create_as_dir().unwrap();
+    let store: Arc<dyn BackupStorage> = Arc::new(LocalFs::new(backup_dir.path().to_path_buf()));
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+    let catalog_dir = TempPath::new();
+    catalog_dir.create_as_dir().unwrap();
+    let catalog = Catalog::open(catalog_dir.path()).unwrap();
+
+    let manifest1 = synthetic_manifest(1, 1_700_000_000, &["d1"]);
+    let manifest2 = synthetic_manifest(2, 1_700_086_400, &["d2"]);
+    let handle1 = write_manifest(&mut rt, &store, &manifest1);
+    let handle2 = write_manifest(&mut rt, &store, &manifest2);
+    catalog.record(handle1.clone(), &manifest1).unwrap();
+    catalog.record(handle2.clone(), &manifest2).unwrap();
+
+    let entries = catalog.list().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].version, 1);
+    assert_eq!(entries[1].version, 2);
+
+    assert_eq!(
+        catalog.find_by_version(1).unwrap().unwrap().manifest_handle,
+        handle1
+    );
+    assert!(catalog.find_by_version(3).unwrap().is_none());
+
+    assert_eq!(
+        catalog
+            .find_by_root_hash(manifest2.root_hash)
+            .unwrap()
+            .unwrap()
+            .version,
+        2
+    );
+    assert!(catalog
+        .find_by_root_hash(HashValue::sha3_256_of(b"no-such-snapshot"))
+        .unwrap()
+        .is_none());
+
+    catalog.remove(1).unwrap();
+    assert!(catalog.find_by_version(1).unwrap().is_none());
+    assert!(catalog.find_by_root_hash(manifest1.root_hash).unwrap().is_none());
+    assert_eq!(catalog.list().unwrap().len(), 1);
+    // Removing an already-absent version is a no-op, not an error.
+    catalog.remove(1).unwrap();
+
+    // rebuild scans BackupStorage directly, so version 1's manifest (never deleted from storage,
+    // only dropped from the catalog above) comes back.
+    let rebuild_dir = TempPath::new();
+    rebuild_dir.create_as_dir().unwrap();
+    let rebuilt = rt
+        .block_on(Catalog::rebuild(store.as_ref(), rebuild_dir.path()))
+        .unwrap();
+    assert_eq!(rebuilt.list().unwrap().len(), 2);
+    assert!(rebuilt.find_by_version(1).unwrap().is_some());
+}
+
+/// A restore given the wrong passphrase must fail outright (key fingerprint mismatch, caught
+/// before any chunk is even read), never silently produce garbage state.
+#[test]
+fn restore_fails_with_wrong_passphrase() {
+    let (_src_db_dir, src_db) = tmp_db_with_random_content();
+    let (_tgt_db_dir, tgt_db) = tmp_db_empty();
+    let backup_dir = TempPath::new();
+    backup_dir.create_as_dir().unwrap();
+    let store: Arc<dyn BackupStorage> = Arc::new(LocalFs::new(backup_dir.path().to_path_buf()));
+
+    let config = NodeConfig::random();
+    let mut rt = start_backup_service(config.storage.backup_service_port, src_db);
+    let client = Arc::new(BackupServiceClient::new(config.storage.backup_service_port));
+    let (version, _) = rt.block_on(client.get_latest_state_root()).unwrap();
+    let manifest_handle = rt
+        .block_on(
+            StateSnapshotBackupController::new(
+                StateSnapshotBackupOpt { version },
+                GlobalBackupOpt {
+                    avg_chunk_size: 64,
+                    min_chunk_size: 16,
+                    max_chunk_size: 500,
+                    crypto: CryptoOpt {
+                        crypt_mode: CryptMode::Encrypt,
+                        key_file: None,
+                        passphrase: Some("correct horse battery staple".to_string()),
+                    },
+                },
+                client,
+                Arc::clone(&store),
+                None,
+            )
+            .run(),
+        )
+        .unwrap();
+
+    let result = rt.block_on(
+        StateSnapshotRestoreController::new(
+            StateSnapshotRestoreOpt {
+                manifest_handle,
+                version: PRE_GENESIS_VERSION,
+                crypto: CryptoOpt {
+                    crypt_mode: CryptMode::Encrypt,
+                    key_file: None,
+                    passphrase: Some("wrong passphrase entirely".to_string()),
+                },
+            },
+            store,
+            tgt_db,
+        )
+        .run(),
+    );
+    assert!(result.is_err());
+}
+
+/// A manifest whose bytes were tampered with after being written (but whose `.mac` wasn't updated
+/// to match) must be rejected before a restore trusts anything else in it.
+#[test]
+fn restore_rejects_tampered_manifest() {
+    let (_src_db_dir, src_db) = tmp_db_with_random_content();
+    let (_tgt_db_dir, tgt_db) = tmp_db_empty();
+    let backup_dir = TempPath::new();
+    backup_dir.create_as_dir().unwrap();
+    let store: Arc<dyn BackupStorage> = Arc::new(LocalFs::new(backup_dir.path().to_path_buf()));
+
+    let crypto = CryptoOpt {
+        crypt_mode: CryptMode::Encrypt,
+        key_file: None,
+        passphrase: Some("correct horse battery staple".to_string()),
+    };
+
+    let config = NodeConfig::random();
+    let mut rt = start_backup_service(config.storage.backup_service_port, src_db);
+    let client = Arc::new(BackupServiceClient::new(config.storage.backup_service_port));
+    let (version, _) = rt.block_on(client.get_latest_state_root()).unwrap();
+    let manifest_handle = rt
+        .block_on(
+            StateSnapshotBackupController::new(
+                StateSnapshotBackupOpt { version },
+                GlobalBackupOpt {
+                    avg_chunk_size: 64,
+                    min_chunk_size: 16,
+                    max_chunk_size: 500,
+                    crypto: crypto.clone(),
+                },
+                client,
+                Arc::clone(&store),
+                None,
+            )
+            .run(),
+        )
+        .unwrap();
+
+    // Rewrite the manifest in place (its `.mac` still authenticates the old bytes), the same way
+    // an attacker tampering with a manifest at rest would, e.g. to rewrite it as unencrypted.
+    let manifest_path = backup_dir.path().join(&manifest_handle);
+    let mut manifest: StateSnapshotBackupManifest =
+        serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+    manifest.created_at += 1;
+    std::fs::write(&manifest_path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+    let result = rt.block_on(
+        StateSnapshotRestoreController::new(
+            StateSnapshotRestoreOpt {
+                manifest_handle,
+                version: PRE_GENESIS_VERSION,
+                crypto,
+            },
+            store,
+            tgt_db,
+        )
+        .run(),
+    );
+    assert!(result.is_err());
+}
+
+/// `--crypt-mode` must match what the manifest actually claims in both directions: a restore
+/// asking for `none` against an encrypted manifest, or `encrypt` against a plaintext one, has to
+/// fail rather than silently trust (or silently ignore) the manifest's own `crypt_mode`.
+#[test]
+fn restore_rejects_crypt_mode_mismatch_in_both_directions() {
+    let (_plain_db_dir, plain_db) = tmp_db_with_random_content();
+    let (_enc_db_dir, enc_db) = tmp_db_with_random_content();
+    let (_tgt_db_dir, tgt_db) = tmp_db_empty();
+    let backup_dir = TempPath::new();
+    backup_dir.create_as_dir().unwrap();
+    let store: Arc<dyn BackupStorage> = Arc::new(LocalFs::new(backup_dir.path().to_path_buf()));
+
+    let global_opt = |crypto: CryptoOpt| GlobalBackupOpt {
+        avg_chunk_size: 64,
+        min_chunk_size: 16,
+        max_chunk_size: 500,
+        crypto,
+    };
+
+    let plain_config = NodeConfig::random();
+    let mut plain_rt = start_backup_service(plain_config.storage.backup_service_port, plain_db);
+    let plain_client = Arc::new(BackupServiceClient::new(
+        plain_config.storage.backup_service_port,
+    ));
+    let (plain_version, _) = plain_rt.block_on(plain_client.get_latest_state_root()).unwrap();
+    let plain_manifest = plain_rt
+        .block_on(
+            StateSnapshotBackupController::new(
+                StateSnapshotBackupOpt {
+                    version: plain_version,
+                },
+                global_opt(CryptoOpt::default()),
+                plain_client,
+                Arc::clone(&store),
+                None,
+            )
+            .run(),
+        )
+        .unwrap();
+
+    let enc_crypto = CryptoOpt {
+        crypt_mode: CryptMode::Encrypt,
+        key_file: None,
+        passphrase: Some("correct horse battery staple".to_string()),
+    };
+    let enc_config = NodeConfig::random();
+    let mut enc_rt = start_backup_service(enc_config.storage.backup_service_port, enc_db);
+    let enc_client = Arc::new(BackupServiceClient::new(
+        enc_config.storage.backup_service_port,
+    ));
+    let (enc_version, _) = enc_rt.block_on(enc_client.get_latest_state_root()).unwrap();
+    let enc_manifest = enc_rt
+        .block_on(
+            StateSnapshotBackupController::new(
+                StateSnapshotBackupOpt { version: enc_version },
+                global_opt(enc_crypto.clone()),
+                enc_client,
+                Arc::clone(&store),
+                None,
+            )
+            .run(),
+        )
+        .unwrap();
+
+    // `--crypt-mode encrypt` against a manifest that claims `none`.
+    let result = plain_rt.block_on(
+        StateSnapshotRestoreController::new(
+            StateSnapshotRestoreOpt {
+                manifest_handle: plain_manifest,
+                version: PRE_GENESIS_VERSION,
+                crypto: enc_crypto.clone(),
+            },
+            Arc::clone(&store),
+            Arc::clone(&tgt_db),
+        )
+        .run(),
+    );
+    assert!(result.is_err());
+
+    // `--crypt-mode none` against a manifest that claims `encrypt`.
+    let result = enc_rt.block_on(
+        StateSnapshotRestoreController::new(
+            StateSnapshotRestoreOpt {
+                manifest_handle: enc_manifest,
+                version: PRE_GENESIS_VERSION,
+                crypto: CryptoOpt::default(),
+            },
+            store,
+            tgt_db,
+        )
+        .run(),
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn verify_passes_a_good_backup_and_fails_a_tampered_one() {
+    let (_src_db_dir, src_db) = tmp_db_with_random_content();
+    let backup_dir = TempPath::new();
+    backup_dir.create_as_dir().unwrap();
+    let store: Arc<dyn BackupStorage> = Arc::new(LocalFs::new(backup_dir.path().to_path_buf()));
+
+    let config = NodeConfig::random();
+    let mut rt = start_backup_service(config.storage.backup_service_port, src_db);
+    let client = Arc::new(BackupServiceClient::new(config.storage.backup_service_port));
+    let (version, _) = rt.block_on(client.get_latest_state_root()).unwrap();
+    let manifest_handle = rt
+        .block_on(
+            StateSnapshotBackupController::new(
+                StateSnapshotBackupOpt { version },
+                GlobalBackupOpt {
+                    avg_chunk_size: 64,
+                    min_chunk_size: 16,
+                    max_chunk_size: 500,
+                    crypto: CryptoOpt::default(),
+                },
+                client,
+                Arc::clone(&store),
+                None,
+            )
+            .run(),
+        )
+        .unwrap();
+
+    let good_report = rt
+        .block_on(
+            StateSnapshotVerifyController::new(
+                StateSnapshotVerifyOpt {
+                    manifest_handle: manifest_handle.clone(),
+                    crypto: CryptoOpt::default(),
+                },
+                Arc::clone(&store),
+            )
+            .run(),
+        )
+        .unwrap();
+    assert!(good_report.root_hash_matches);
+    assert!(good_report.chunk_results.iter().all(|result| result.ok));
+
+    // Overwrite one stored chunk's bytes on disk directly (BackupStorage::create_for_write
+    // refuses to clobber an existing file, same as a real tamper wouldn't go through it either).
+    let manifest_bytes = std::fs::read(backup_dir.path().join(&manifest_handle)).unwrap();
+    let manifest: StateSnapshotBackupManifest = serde_json::from_slice(&manifest_bytes).unwrap();
+    let victim = &manifest.chunks[0];
+    let victim_path = backup_dir.path().join(chunk_handle(&victim.digest, None));
+    std::fs::write(&victim_path, b"not the original chunk bytes").unwrap();
+
+    let bad_report = rt
+        .block_on(
+            StateSnapshotVerifyController::new(
+                StateSnapshotVerifyOpt {
+                    manifest_handle,
+                    crypto: CryptoOpt::default(),
+                },
+                store,
+            )
+            .run(),
+        )
+        .unwrap();
+    assert!(!bad_report.root_hash_matches);
+    assert!(bad_report
+        .chunk_results
+        .iter()
+        .any(|result| result.digest == victim.digest && !result.ok));
+}
+
+/// `PartBuffer` should hold writes back until `part_size` is reached, hand back exactly what was
+/// buffered once it is, and never produce an empty part.
+#[test]
+fn part_buffer_flushes_once_part_size_is_reached() {
+    let mut parts = PartBuffer::new(4);
+
+    // Under part_size: buffered, nothing flushed yet.
+    assert!(parts.push(b"ab").is_none());
+    // Crossing part_size: the whole buffer (both pushes) comes back as one part.
+    let (part_number, bytes) = parts.push(b"cd").unwrap();
+    assert_eq!(part_number, 1);
+    assert_eq!(bytes.as_ref(), &b"abcd"[..]);
+
+    // Nothing buffered: flushing is a no-op, not an empty part.
+    assert!(parts.flush().is_none());
+}
+
+/// Part numbers must be assigned in order, starting at 1, across however many parts a writer
+/// produces, whether they're flushed by crossing `part_size` or by a final `flush()`.
+#[test]
+fn part_buffer_assigns_part_numbers_in_order() {
+    let mut parts = PartBuffer::new(2);
+
+    let (first, _) = parts.push(b"ab").unwrap();
+    let (second, _) = parts.push(b"cd").unwrap();
+    parts.push(b"e");
+    let (third, _) = parts.flush().unwrap();
+    assert_eq!((first, second, third), (1, 2, 3));
+}
+
+/// A zero-byte write (nothing ever pushed) must never produce a part at all, since S3 rejects
+/// completing a multipart upload with zero parts; the writer has to fall back to a plain
+/// `put_object` instead, which only happens when no part was ever produced in the first place.
+#[test]
+fn part_buffer_produces_no_part_for_a_zero_byte_file() {
+    let mut parts = PartBuffer::new(4);
+    assert!(parts.flush().is_none());
+}
+
+#[test]
+fn s3_key_joins_prefix_and_file_name() {
+    let client = S3Client::new(Region::UsEast1);
+    let with_prefix = S3BackupStorage::new(
+        client,
+        "my-bucket".to_string(),
+        Some("backups/node1".to_string()),
+        1024 * 1024,
+    );
+    assert_eq!(with_prefix.key("state_snapshot/1/manifest.json"), "backups/node1/state_snapshot/1/manifest.json");
+
+    let without_prefix = S3BackupStorage::new(
+        S3Client::new(Region::UsEast1),
+        "my-bucket".to_string(),
+        None,
+        1024 * 1024,
+    );
+    assert_eq!(
+        without_prefix.key("state_snapshot/1/manifest.json"),
+        "state_snapshot/1/manifest.json"
+    );
+}